@@ -0,0 +1,92 @@
+//! The GDT, TSS, and per-CPU state that usermode and the fault handlers rely on.
+//!
+//! Segment selector values are fixed at compile time instead of read back from
+//! `GlobalDescriptorTable::add_entry` so `init_syscall_msrs` can hand them straight to
+//! `Star::write`, which requires `KERNEL_DATA_SELECTOR == KERNEL_CODE_SELECTOR + 8` (the
+//! `syscall` convention) and `USER_CODE_SELECTOR == USER_DATA_SELECTOR + 8` (the `sysret`
+//! convention) -- [`init`] must build the table in exactly this order.
+
+use lazy_static::lazy_static;
+use x86_64::instructions::segmentation;
+use x86_64::instructions::tables::load_tss;
+use x86_64::registers::model_specific::KernelGsBase;
+use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::tss::TaskStateSegment;
+use x86_64::PrivilegeLevel;
+use x86_64::VirtAddr;
+
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
+const KERNEL_STACK_SIZE: usize = 4096 * 5;
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+
+pub const KERNEL_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(1, PrivilegeLevel::Ring0);
+pub const KERNEL_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(2, PrivilegeLevel::Ring0);
+pub const USER_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(3, PrivilegeLevel::Ring3);
+pub const USER_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(4, PrivilegeLevel::Ring3);
+const TSS_SELECTOR: SegmentSelector = SegmentSelector::new(5, PrivilegeLevel::Ring0);
+
+/// Per-CPU state reachable through `KernelGsBase`/`swapgs`.
+///
+/// `kernel_stack_top` must stay at offset 0 and `syscall_scratch` at offset 8 -- the naked
+/// asm in `interrupts::syscall_handler` addresses them directly as `gs:[0]`/`gs:[8]`.
+#[repr(C)]
+pub struct CpuLocal {
+	kernel_stack_top: VirtAddr,
+	syscall_scratch: u64,
+}
+
+lazy_static! {
+	static ref CPU_LOCAL: CpuLocal = {
+		static mut KERNEL_STACK: [u8; KERNEL_STACK_SIZE] = [0; KERNEL_STACK_SIZE];
+		let stack_start = VirtAddr::from_ptr(unsafe { &KERNEL_STACK });
+		CpuLocal {
+			kernel_stack_top: stack_start + KERNEL_STACK_SIZE as u64,
+			syscall_scratch: 0,
+		}
+	};
+}
+
+lazy_static! {
+	static ref TSS: TaskStateSegment = {
+		let mut tss = TaskStateSegment::new();
+
+		tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
+			static mut DOUBLE_FAULT_STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
+			let stack_start = VirtAddr::from_ptr(unsafe { &DOUBLE_FAULT_STACK });
+			stack_start + DOUBLE_FAULT_STACK_SIZE as u64
+		};
+
+		// Ring-3 -> ring-0 transitions through the IDT land here, same stack the syscall
+		// entry stub switches to via CpuLocal::kernel_stack_top.
+		tss.privilege_stack_table[0] = CPU_LOCAL.kernel_stack_top;
+
+		tss
+	};
+}
+
+lazy_static! {
+	static ref GDT: GlobalDescriptorTable = {
+		let mut gdt = GlobalDescriptorTable::new();
+		gdt.add_entry(Descriptor::kernel_code_segment());
+		gdt.add_entry(Descriptor::kernel_data_segment());
+		gdt.add_entry(Descriptor::user_data_segment());
+		gdt.add_entry(Descriptor::user_code_segment());
+		gdt.add_entry(Descriptor::tss_segment(&TSS));
+		gdt
+	};
+}
+
+/// Loads the GDT and TSS, reloads the segment registers to match, and points
+/// `KernelGsBase` at this CPU's [`CpuLocal`] so the syscall entry stub can find it.
+pub fn init() {
+	GDT.load();
+
+	unsafe {
+		segmentation::load_cs(KERNEL_CODE_SELECTOR);
+		segmentation::load_ss(KERNEL_DATA_SELECTOR);
+		load_tss(TSS_SELECTOR);
+
+		KernelGsBase::write(VirtAddr::new(&*CPU_LOCAL as *const CpuLocal as u64));
+	}
+}
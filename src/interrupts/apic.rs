@@ -0,0 +1,227 @@
+//! Local APIC + I/O APIC interrupt routing, replacing the legacy 8259 PIC.
+//!
+//! The PIC is masked off in [`init`] and never touched again. The Local APIC's own timer
+//! takes over `InterruptIndex::Timer`, and the I/O APIC -- discovered by walking the ACPI
+//! MADT -- takes over the keyboard line that used to run through the PIC.
+
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::structures::paging::{FrameAllocator, Mapper, OffsetPageTable, Page, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::instructions::port::Port;
+use super::hardware::InterruptIndex;
+
+const LAPIC_PHYS_BASE: u64 = 0xFEE0_0000;
+
+const REG_SPURIOUS: usize = 0x0F0;
+const REG_EOI: usize = 0x0B0;
+const REG_TIMER_LVT: usize = 0x320;
+const REG_TIMER_INITIAL_COUNT: usize = 0x380;
+const REG_TIMER_DIVIDE: usize = 0x3E0;
+
+const TIMER_PERIODIC: u32 = 1 << 17;
+const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+const SPURIOUS_VECTOR: u32 = 0xFF;
+
+/// MMIO view of the Local APIC's register page, mapped once in [`init`].
+struct LocalApic {
+	base: VirtAddr,
+}
+
+impl LocalApic {
+	unsafe fn write(&self, reg: usize, value: u32) {
+		core::ptr::write_volatile((self.base.as_u64() as usize + reg) as *mut u32, value)
+	}
+}
+
+static mut LAPIC: Option<LocalApic> = None;
+
+/// Masks the legacy PIC, maps and arms the Local APIC timer, and routes the keyboard IRQ
+/// through the I/O APIC described by the ACPI MADT reached from the RSDP at `rsdp_addr`.
+/// If no MADT or no I/O APIC is found, the keyboard IRQ is left unrouted.
+///
+/// `rsdp_addr` is a physical address handed to us by the bootloader/UEFI, so the ACPI walk
+/// reads it (and everything it points at) through `physical_memory_offset`, the same way
+/// `paging::active_level_4_table` reaches page-table frames -- physical memory here is not
+/// identity-mapped.
+pub fn init(
+	rsdp_addr: PhysAddr,
+	physical_memory_offset: VirtAddr,
+	mapper: &mut OffsetPageTable,
+	frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) {
+	unsafe {
+		disable_legacy_pic();
+
+		let lapic_page = Page::containing_address(VirtAddr::new(LAPIC_PHYS_BASE));
+		map_mmio_page(PhysAddr::new(LAPIC_PHYS_BASE), lapic_page, mapper, frame_allocator);
+		let lapic = LocalApic { base: lapic_page.start_address() };
+
+		lapic.write(REG_SPURIOUS, APIC_SOFTWARE_ENABLE | SPURIOUS_VECTOR);
+		lapic.write(REG_TIMER_DIVIDE, 0b1011); // divide by 1
+		lapic.write(REG_TIMER_LVT, TIMER_PERIODIC | InterruptIndex::Timer as u8 as u32);
+		lapic.write(REG_TIMER_INITIAL_COUNT, 10_000_000);
+
+		LAPIC = Some(lapic);
+
+		if let Some(madt) = madt::find(rsdp_addr, physical_memory_offset) {
+			route_keyboard_irq(&madt, mapper, frame_allocator);
+		}
+	}
+}
+
+/// Signals end-of-interrupt on the Local APIC, replacing `PICS.notify_end_of_interrupt`.
+pub fn end_of_interrupt() {
+	unsafe {
+		if let Some(lapic) = &LAPIC {
+			lapic.write(REG_EOI, 0);
+		}
+	}
+}
+
+unsafe fn disable_legacy_pic() {
+	let mut master_data: Port<u8> = Port::new(0x21);
+	let mut slave_data: Port<u8> = Port::new(0xA1);
+	master_data.write(0xFF);
+	slave_data.write(0xFF);
+}
+
+unsafe fn map_mmio_page(
+	phys: PhysAddr,
+	page: Page,
+	mapper: &mut OffsetPageTable,
+	frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) {
+	let frame = PhysFrame::<Size4KiB>::containing_address(phys);
+	let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+	mapper.map_to(page, frame, flags, frame_allocator)
+		.expect("failed to map an APIC MMIO page")
+		.flush();
+}
+
+unsafe fn route_keyboard_irq(
+	found: &madt::Madt,
+	mapper: &mut OffsetPageTable,
+	frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) {
+	if found.io_apic_addr.as_u64() == 0 {
+		return;
+	}
+
+	let page = Page::containing_address(VirtAddr::new(found.io_apic_addr.as_u64()));
+	map_mmio_page(found.io_apic_addr, page, mapper, frame_allocator);
+	let io_apic_base = page.start_address().as_u64() as usize;
+
+	let redirection_low = InterruptIndex::Keyboard as u8 as u32; // fixed delivery, physical dest, unmasked
+	let low_index = 0x10 + found.keyboard_gsi * 2;
+	io_apic_write(io_apic_base, low_index, redirection_low);
+	io_apic_write(io_apic_base, low_index + 1, 0); // destination APIC id 0
+}
+
+unsafe fn io_apic_write(base: usize, reg: u32, value: u32) {
+	core::ptr::write_volatile(base as *mut u32, reg);
+	core::ptr::write_volatile((base + 0x10) as *mut u32, value);
+}
+
+/// Just enough of the ACPI table layout to walk RSDP -> RSDT/XSDT -> MADT and pull out the
+/// I/O APIC address plus a keyboard (IRQ 1) interrupt source override, if the firmware gives
+/// us one.
+///
+/// `rsdp_addr` and everything it chains to are physical addresses; every read here goes
+/// through `physical_memory_offset` rather than treating the physical address as a usable
+/// pointer directly, since physical memory isn't identity-mapped.
+mod madt {
+	use x86_64::{PhysAddr, VirtAddr};
+
+	pub struct Madt {
+		pub io_apic_addr: PhysAddr,
+		pub keyboard_gsi: u32,
+	}
+
+	#[repr(C, packed)]
+	struct Rsdp {
+		signature: [u8; 8],
+		checksum: u8,
+		oem_id: [u8; 6],
+		revision: u8,
+		rsdt_addr: u32,
+		length: u32,
+		xsdt_addr: u64,
+	}
+
+	#[repr(C, packed)]
+	struct SdtHeader {
+		signature: [u8; 4],
+		length: u32,
+		revision: u8,
+		checksum: u8,
+		oem_id: [u8; 6],
+		oem_table_id: [u8; 8],
+		oem_revision: u32,
+		creator_id: u32,
+		creator_revision: u32,
+	}
+
+	fn phys_to_virt(physical_memory_offset: VirtAddr, phys: u64) -> VirtAddr {
+		physical_memory_offset + phys
+	}
+
+	pub unsafe fn find(rsdp_addr: PhysAddr, physical_memory_offset: VirtAddr) -> Option<Madt> {
+		let rsdp_ptr = phys_to_virt(physical_memory_offset, rsdp_addr.as_u64()).as_ptr::<Rsdp>();
+		let rsdp = &*rsdp_ptr;
+		let use_xsdt = rsdp.revision >= 2;
+		let sdt_addr = if use_xsdt { rsdp.xsdt_addr } else { rsdp.rsdt_addr as u64 };
+		let sdt_ptr = phys_to_virt(physical_memory_offset, sdt_addr).as_ptr::<SdtHeader>();
+		let sdt = &*sdt_ptr;
+
+		let entry_size = if use_xsdt { 8 } else { 4 };
+		let entry_count = (sdt.length as usize - core::mem::size_of::<SdtHeader>()) / entry_size;
+		let entries = (sdt_ptr as *const u8).add(core::mem::size_of::<SdtHeader>());
+
+		for i in 0..entry_count {
+			let entry_addr = if use_xsdt {
+				*(entries as *const u64).add(i)
+			} else {
+				*(entries as *const u32).add(i) as u64
+			};
+
+			let header_ptr = phys_to_virt(physical_memory_offset, entry_addr).as_ptr::<SdtHeader>();
+			let header = &*header_ptr;
+			if &header.signature == b"APIC" {
+				return Some(parse(entry_addr, header.length, physical_memory_offset));
+			}
+		}
+
+		None
+	}
+
+	unsafe fn parse(madt_addr: u64, length: u32, physical_memory_offset: VirtAddr) -> Madt {
+		let madt_virt = phys_to_virt(physical_memory_offset, madt_addr).as_u64() as usize;
+		let mut cursor = madt_virt + core::mem::size_of::<SdtHeader>() + 8; // skip local_apic_addr + flags
+		let end = madt_virt + length as usize;
+
+		let mut io_apic_addr = PhysAddr::new(0);
+		let mut keyboard_gsi = 1; // identity-mapped default if the firmware has no override
+
+		while cursor < end {
+			let entry_type = *(cursor as *const u8);
+			let entry_len = *((cursor + 1) as *const u8) as usize;
+
+			match entry_type {
+				1 => { // I/O APIC
+					let address = *((cursor + 4) as *const u32);
+					io_apic_addr = PhysAddr::new(address as u64);
+				}
+				2 => { // Interrupt Source Override
+					let source = *((cursor + 3) as *const u8);
+					if source == 1 {
+						keyboard_gsi = *((cursor + 4) as *const u32);
+					}
+				}
+				_ => {}
+			}
+
+			cursor += entry_len;
+		}
+
+		Madt { io_apic_addr, keyboard_gsi }
+	}
+}
@@ -0,0 +1,128 @@
+//! CPU exception handlers.
+//!
+//! `breakpoint_handler`/`debug_handler`/`double_fault_handler` are ordinary
+//! `extern "x86-interrupt"` functions: they never terminate a process, so the
+//! compiler-generated `iretq` epilogue resuming exactly where the fault happened is
+//! correct. The others always either terminate the faulting process or panic the kernel --
+//! the faulting context is never resumed -- so they are naked trampolines built with
+//! [`fault_trampoline!`] that call into Rust through [`dispatch_fault`] and load whatever
+//! stack pointer it returns (the next runnable process's, same as
+//! `SyscallCommand::Yield`/`Terminate`) before `iretq`.
+
+use x86_64::structures::idt::{InterruptStackFrame, PageFaultErrorCode};
+use x86_64::registers::control::Cr2;
+use crate::println;
+
+use super::helper_macros::*;
+
+const DIVIDE_VECTOR: u64 = 0;
+const STACK_SEGMENT_VECTOR: u64 = 12;
+const GP_VECTOR: u64 = 13;
+const PAGE_FAULT_VECTOR: u64 = 14;
+const ALIGNMENT_VECTOR: u64 = 17;
+
+pub extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+	println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+}
+
+pub extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
+	println!("EXCEPTION: DEBUG\n{:#?}", stack_frame);
+}
+
+pub extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame, error_code: u64) -> ! {
+	panic!("DOUBLE FAULT, error code: {:#x}\n{:#?}", error_code, stack_frame);
+}
+
+/// Builds a naked trampoline that normalizes the hardware-pushed frame to
+/// `[vector, error_code, <original iretq frame>]` (pushing a dummy `0` error code for
+/// vectors the CPU doesn't supply one for) and hands `rsp` straight off to
+/// [`dispatch_fault`] -- unlike `syscall_handler`, there's no `interrupt_push!()` first,
+/// because the faulting process's own registers are never resumed.
+macro_rules! fault_trampoline {
+	($name:ident, $vector:expr, no_error_code) => {
+		#[naked]
+		pub unsafe extern fn $name() -> ! {
+			llvm_asm!("push 0" : : : : "intel", "volatile"); // this vector has no hardware error code
+			llvm_asm!("push $0" : : "i"($vector as u64) : : "intel", "volatile");
+			fault_trampoline_common!();
+		}
+	};
+	($name:ident, $vector:expr, has_error_code) => {
+		#[naked]
+		pub unsafe extern fn $name() -> ! {
+			llvm_asm!("push $0" : : "i"($vector as u64) : : "intel", "volatile");
+			fault_trampoline_common!();
+		}
+	};
+}
+
+macro_rules! fault_trampoline_common {
+	() => {
+		llvm_asm!( "
+				mov %rsp, %rdi //; Pass rsp (-> vector, error_code, iretq frame) as the only argument
+				call ${0:c}
+				mov %rax, %rsp //; switch onto the next scheduled process's saved frame
+				": : "i"(dispatch_fault as u64) : "memory", "rsp", "rdi", "rax" : "volatile", "alignstack");
+
+		// Pops whatever process dispatch_fault switched us to back onto the stack it saved
+		// when it was last descheduled -- never this fault's own registers.
+		interrupt_pop!();
+		llvm_asm!("iretq" : : : : "intel", "volatile");
+		unreachable!();
+	};
+}
+
+fault_trampoline!(divide_handler, DIVIDE_VECTOR, no_error_code);
+fault_trampoline!(stack_segment_handler, STACK_SEGMENT_VECTOR, has_error_code);
+fault_trampoline!(gp_handler, GP_VECTOR, has_error_code);
+fault_trampoline!(alignment_handler, ALIGNMENT_VECTOR, has_error_code);
+fault_trampoline!(page_fault_handler, PAGE_FAULT_VECTOR, has_error_code);
+
+/// Called by every [`fault_trampoline!`] with `stack_p` pointing at the
+/// `[vector, error_code, <original iretq frame>]` triple it just pushed, before anything
+/// else has touched the stack. Always either terminates the faulting process and returns
+/// the next scheduled process's saved-frame stack pointer, or panics -- never returns
+/// `stack_p` itself, since the faulting process's own registers are never resumed.
+#[inline(never)]
+extern "C" fn dispatch_fault(stack_p: usize) -> usize {
+	use crate::processes::PROCESS_MANAGER;
+
+	let vector = unsafe { *(stack_p as *const u64) };
+	let error_code = unsafe { *((stack_p + 8) as *const u64) };
+	let stack_frame = unsafe { &*((stack_p + 16) as *const InterruptStackFrame) };
+	let name = fault_name(vector);
+
+	if vector == PAGE_FAULT_VECTOR {
+		// Decoded here, rather than just logged as a raw error code, so a future
+		// demand-paging mechanism has Cr2/the access kind to hook a real recovery path off
+		// of -- there isn't one yet, so every page fault still falls through below.
+		let faulting_address = Cr2::read();
+		let flags = PageFaultErrorCode::from_bits_truncate(error_code);
+		println!("page-fault at {:?}, error code: {:?}", faulting_address, flags);
+	} else {
+		println!("{} fault, error code: {:#x}", name, error_code);
+	}
+
+	if is_ring3(stack_frame) {
+		println!("{}: terminating faulting process", name);
+		PROCESS_MANAGER.lock()
+			.end_current_process().as_u64() as usize
+	} else {
+		panic!("{} fault in ring 0:\n{:#?}", name, stack_frame);
+	}
+}
+
+fn fault_name(vector: u64) -> &'static str {
+	match vector {
+		DIVIDE_VECTOR => "divide-by-zero",
+		STACK_SEGMENT_VECTOR => "stack",
+		GP_VECTOR => "general-protection",
+		PAGE_FAULT_VECTOR => "page-fault",
+		ALIGNMENT_VECTOR => "alignment-check",
+		_ => "unknown",
+	}
+}
+
+fn is_ring3(stack_frame: &InterruptStackFrame) -> bool {
+	(stack_frame.code_segment & 0b11) == 3
+}
@@ -0,0 +1,55 @@
+//! Vector numbers and handlers for the hardware interrupts `create_idt` installs beyond the
+//! CPU exceptions in `cpu.rs`.
+
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+use super::apic;
+use super::helper_macros::*;
+
+/// Vector numbers used for IRQs routed through the Local/IO APIC (see `apic::init`).
+#[derive(Debug, Copy, Clone)]
+#[repr(u8)]
+pub enum InterruptIndex {
+	Timer = 32,
+	Keyboard = 33,
+}
+
+// Naked like `syscall_handler`/the `cpu` fault trampolines: the timer tick preempts
+// whatever process is running, so this may need to switch to a different process's stack
+// before returning, which an ordinary `extern "x86-interrupt"` epilogue can't do.
+#[naked]
+pub unsafe extern fn timer_interrupt_handler() -> ! {
+	interrupt_push!();
+
+	llvm_asm!( "
+			mov %rsp, %rdi //; Pass rsp as first argument
+			call ${0:c}
+			mov %rax, %rsp
+			": : "i"(timer_tick as u64) : "memory", "rsp", "rdi", "rax" : "volatile", "alignstack");
+
+	interrupt_pop!();
+	llvm_asm!("iretq" ::: : "intel", "volatile");
+	unreachable!();
+}
+
+#[inline(never)]
+extern "C" fn timer_tick(stack_p: usize) -> usize {
+	use crate::processes::PROCESS_MANAGER;
+
+	// The next process's stack must be loaded before the caller's iretq, so EOI has to be
+	// signaled first -- nothing past this point is guaranteed to run on this stack again.
+	apic::end_of_interrupt();
+
+	PROCESS_MANAGER.lock()
+		.yield_current_process(VirtAddr::new(stack_p as u64)).as_u64() as usize
+}
+
+pub extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+	use x86_64::instructions::port::Port;
+
+	let mut data_port: Port<u8> = Port::new(0x60);
+	let _scancode: u8 = unsafe { data_port.read() };
+	// TODO: decode the scancode into a keypress instead of just draining the controller.
+
+	apic::end_of_interrupt();
+}
@@ -6,11 +6,15 @@ use hardware::InterruptIndex;
 use lazy_static::lazy_static;
 use num_enum::TryFromPrimitive;
 use crate::println;
+use crate::gdt;
 
 use helper_macros::*;
 use core::convert::TryFrom;
 use x86_64::VirtAddr;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
 
+mod apic;
 mod cpu;
 pub mod hardware;
 
@@ -19,63 +23,95 @@ pub mod hardware;
 pub enum SyscallCommand {
 	Yield = 10,
 	Terminate,
+	SemaphoreCreate,
+	SemaphoreWait,
+	SemaphoreSignal,
 }
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = create_idt();
 }
 
-pub fn interrupt_init() {
+pub fn interrupt_init(
+	rsdp_addr: x86_64::PhysAddr,
+	physical_memory_offset: VirtAddr,
+	mapper: &mut x86_64::structures::paging::OffsetPageTable,
+	frame_allocator: &mut impl x86_64::structures::paging::FrameAllocator<x86_64::structures::paging::Size4KiB>,
+) {
+	gdt::init();
 	IDT.load();
-	unsafe { hardware::PICS.lock().initialize() }
+	apic::init(rsdp_addr, physical_memory_offset, mapper, frame_allocator);
+	init_syscall_msrs();
 }
 
-// TODO: Actually have a kernel stack pointer
-// The user calling this syscall better have interrupt disabled, how are they going to do that in usermode?
-// who knows, good thing everyone is in kernel mode I suppose.
+/// Programs `STAR`/`LSTAR`/`SFMASK` so the `syscall`/`sysret` pair can move between ring 0
+/// and ring 3 entirely in hardware, and flips on `EFER.SCE` to enable the instructions.
+fn init_syscall_msrs() {
+	unsafe {
+		Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS);
+		Star::write(
+			gdt::USER_CODE_SELECTOR, gdt::USER_DATA_SELECTOR,
+			gdt::KERNEL_CODE_SELECTOR, gdt::KERNEL_DATA_SELECTOR,
+		).expect("STAR selectors aren't laid out the way sysret expects");
+		LStar::write(VirtAddr::new(syscall_handler as u64));
+		// The CPU clears these flags on entry, so interrupts are disabled on entry to
+		// `syscall_handler` by hardware instead of by convention.
+		SFMask::write(RFlags::INTERRUPT_FLAG);
+	}
+}
+
+// `gs` is swapped to `KernelGSBase`, which points at this CPU's `gdt::CpuLocal`, whose
+// first field is the top of this CPU's dedicated kernel stack (`TSS.privilege_stack_table[0]`
+// mirrors the same address so interrupts and faults taken from ring 3 without an IST also
+// land here).
 #[naked]
 pub unsafe extern fn syscall_handler() -> ! {
-	// x86_64::instructions::interrupts::disable();
-	// Make sure not to use any registers, somehow
 	llvm_asm!("
-		  swapgs // Load the TSS as temporary storage lol
-		  mov gs:[28], rsp // Move rsp to temporary 'reserved' location in the TSS
-		  push 0  // I think this should be 0, it works with 0.
-		  push qword ptr gs:[28] // Push original rsp
-		  mov qword ptr gs:[28], 0 // Clear the reserved section again
-          push r11 // Push rflags
-          mov r11, cs // Move cs to temporary register to be pushed, we already pushed r11
-          push r11 // Push code segment
-          push rcx // Push return pointer
-          swapgs // Move everything back
-          "
-          :
-          :
-          :
-          : "intel", "volatile");
-	
+		  swapgs // gs now points at this CPU's CpuLocal
+		  mov gs:[8], rsp // stash the user rsp in CpuLocal::syscall_scratch
+		  mov rsp, gs:[0] // switch onto this CPU's dedicated kernel stack
+		  push qword ptr gs:[8] // user rsp, popped back off just before sysretq
+		  push r11 // user rflags, saved here by the syscall instruction
+		  push rcx // user return rip, saved here by the syscall instruction
+		  swapgs // gs back to the user's value
+		  "
+		  :
+		  :
+		  :
+		  : "intel", "volatile");
+
 	interrupt_push!();
-	
+
 	llvm_asm!( "
+			mov %rdi, %rdx //; stash the syscall argument (e.g. a SemaphoreId) as the 3rd param
 			mov %rsp, %rdi //; Pass rsp as first argument
 			mov %rax, %rsi //; Pass rax as second argument
 			call ${0:c}
 			mov %rax, %rsp
-			": : "i"(internal_syscall as u64) : "memory", "rsp", "rdi", "rsi", "rax" : "volatile", "alignstack");
-	
+			": : "i"(internal_syscall as u64) : "memory", "rsp", "rdi", "rsi", "rdx", "rax" : "volatile", "alignstack");
+
 	interrupt_pop!();
-	// TODO: There is a lot of things wrong here, we are assuming everything is just in kernel space.
-	llvm_asm!("iretq");
+	llvm_asm!("
+		  pop rcx // user return rip
+		  pop r11 // user rflags
+		  pop rsp // user rsp, swaps us back onto the user stack
+		  sysretq // ring 0 -> ring 3, rip from rcx, rflags from r11
+		  "
+		  :
+		  :
+		  :
+		  : "intel", "volatile");
 	unreachable!();
 }
 
 #[inline(never)]
-extern "C" fn internal_syscall(stack_p: usize, call_num: usize) -> usize {
+extern "C" fn internal_syscall(stack_p: usize, call_num: usize, arg: usize) -> usize {
 	use crate::processes::PROCESS_MANAGER;
-	
+	use crate::sync::{self, SemaphoreId, SEMAPHORE_STORE};
+
 	let call_num = SyscallCommand::try_from(call_num as u64)
 		.expect("Invalid Syscall Number");
-	
+
 	match call_num {
 		SyscallCommand::Yield => {
 			PROCESS_MANAGER.lock()
@@ -84,15 +120,62 @@ extern "C" fn internal_syscall(stack_p: usize, call_num: usize) -> usize {
 		SyscallCommand::Terminate => {
 			PROCESS_MANAGER.lock()
 				.end_current_process().as_u64() as usize
+		},
+		SyscallCommand::SemaphoreCreate => {
+			sync::create_semaphore(arg as i32) as usize
+		},
+		SyscallCommand::SemaphoreWait => {
+			let id = arg as SemaphoreId;
+			let store = SEMAPHORE_STORE.read();
+
+			// A bad SemaphoreId is ring-3's mistake, not ring 0's -- terminate just the
+			// calling process instead of `.expect()`-ing the whole kernel down.
+			let semaphore = match store.get(&id) {
+				Some(semaphore) => semaphore,
+				None => return PROCESS_MANAGER.lock().end_current_process().as_u64() as usize,
+			};
+
+			if semaphore.wait() {
+				stack_p
+			} else {
+				let current = PROCESS_MANAGER.lock().current_process_name();
+				semaphore.add_to_wait_queue(current);
+				PROCESS_MANAGER.lock()
+					.yield_current_process(VirtAddr::new(stack_p as u64)).as_u64() as usize
+			}
+		},
+		SyscallCommand::SemaphoreSignal => {
+			let id = arg as SemaphoreId;
+			let store = SEMAPHORE_STORE.read();
+
+			if let Some(semaphore) = store.get(&id) {
+				// A waiter blocked in SemaphoreWait without decrementing count, so if one
+				// is waiting the permit transfers straight to it; only bump count when
+				// there's nobody to hand it to directly, or we'd grant it twice.
+				if let Some(waiter) = semaphore.pop_waiter() {
+					PROCESS_MANAGER.lock().wake_process(waiter);
+				} else {
+					semaphore.signal();
+				}
+			}
+
+			stack_p
 		}
 	}
 }
 
 #[inline(always)]
 pub fn syscall1(call_num: SyscallCommand) -> u64 {
+	syscall2(call_num, 0)
+}
+
+/// Like [`syscall1`], but also passes `arg` through to `internal_syscall`, for calls like
+/// [`SyscallCommand::SemaphoreWait`] that need a [`crate::sync::SemaphoreId`].
+#[inline(always)]
+pub fn syscall2(call_num: SyscallCommand, arg: i64) -> u64 {
 	let ret: u64;
 	unsafe {
-		llvm_asm!("syscall" : "={rax}" (ret) : "{rax}" (call_num as u64) : "rcx", "r11", "memory" : "volatile");
+		llvm_asm!("syscall" : "={rax}" (ret) : "{rax}" (call_num as u64), "{rdi}" (arg) : "rcx", "r11", "memory" : "volatile");
 	}
 	ret
 }
@@ -100,18 +183,25 @@ pub fn syscall1(call_num: SyscallCommand) -> u64 {
 fn create_idt() -> InterruptDescriptorTable {
 	let mut idt = InterruptDescriptorTable::new();
 	idt.breakpoint.set_handler_fn(cpu::breakpoint_handler);
-	idt.page_fault.set_handler_fn(cpu::page_fault_handler);
-	idt.alignment_check.set_handler_fn(cpu::alignment_handler);
 	idt.debug.set_handler_fn(cpu::debug_handler);
-	idt.divide_error.set_handler_fn(cpu::divide_handler);
-	idt.general_protection_fault.set_handler_fn(cpu::gp_handler);
-	idt.stack_segment_fault.set_handler_fn(cpu::stack_segment_handler);
-	
+
 	unsafe {
 		idt.double_fault.set_handler_fn(cpu::double_fault_handler)
 			.set_stack_index(crate::gdt::DOUBLE_FAULT_IST_INDEX);
 	}
-	
+
+	// Same hack as the timer below: these are naked trampolines that terminate the faulting
+	// process and switch stacks, not ordinary extern "x86-interrupt" fns, so they can't be
+	// named directly as a HandlerFunc/HandlerFuncWithErrCode -- go through a raw fn pointer
+	// and transmute instead.
+	unsafe {
+		idt.divide_error.set_handler_fn(core::mem::transmute(cpu::divide_handler as *const ()));
+		idt.general_protection_fault.set_handler_fn(core::mem::transmute(cpu::gp_handler as *const ()));
+		idt.stack_segment_fault.set_handler_fn(core::mem::transmute(cpu::stack_segment_handler as *const ()));
+		idt.alignment_check.set_handler_fn(core::mem::transmute(cpu::alignment_handler as *const ()));
+		idt.page_fault.set_handler_fn(core::mem::transmute(cpu::page_fault_handler as *const ()));
+	}
+
 	// Hack to get around compiler check
 	// (We have to do this because we are removing an argument, which we weren't using)
 	// But rust debug builds have a "bug"? where naked functions are not actually naked
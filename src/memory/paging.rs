@@ -1,5 +1,6 @@
 use x86_64::{VirtAddr, PhysAddr};
-use x86_64::structures::paging::{PageTable, OffsetPageTable, Page, FrameAllocator, Size4KiB, PhysFrame, Mapper};
+use x86_64::structures::paging::{PageTable, OffsetPageTable, Page, FrameAllocator, FrameDeallocator, Size4KiB, PhysFrame, Mapper, PageTableFlags};
+use x86_64::structures::paging::mapper::MapToError;
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
 
 pub struct EmptyFrameAllocator;
@@ -10,45 +11,86 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
 	}
 }
 
-/// A FrameAllocator that returns usable frames from the bootloader's memory map.
+/// A FrameAllocator backed by an intrusive free list: each free frame stores the physical
+/// address of the next free frame inline, at offset 0 of its own (phys-offset-mapped)
+/// memory, so allocation and deallocation are both O(1) and need no separate metadata heap.
+///
+/// Within this module `deallocate_frame` is only called by [`BootInfoFrameAllocator::init`]
+/// to build the initial free list. Frames of a process ended by `SyscallCommand::Terminate`
+/// are not handed back to this allocator.
 pub struct BootInfoFrameAllocator {
-	memory_map: &'static MemoryMap,
-	next: usize,
+	physical_memory_offset: VirtAddr,
+	free_list_head: Option<PhysFrame>,
 }
 
 impl BootInfoFrameAllocator {
-	/// Create a FrameAllocator from the passed memory map.
+	/// Create a FrameAllocator from the passed memory map, threading every usable frame
+	/// onto the free list.
 	///
 	/// This function is unsafe because the caller must guarantee that the passed
 	/// memory map is valid. The main requirement is that all frames that are marked
-	/// as `USABLE` in it are really unused.
-	pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-		BootInfoFrameAllocator {
-			memory_map,
-			next: 0,
+	/// as `USABLE` in it are really unused, and that `physical_memory_offset` is where
+	/// all physical memory is mapped.
+	pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+		let mut allocator = BootInfoFrameAllocator {
+			physical_memory_offset,
+			free_list_head: None,
+		};
+
+		for frame in Self::usable_frames(memory_map) {
+			allocator.deallocate_frame(frame);
 		}
+
+		allocator
 	}
-	
+
 	/// Returns an iterator over the usable frames specified in the memory map.
-	fn usable_frames(&self) -> impl Iterator<Item=PhysFrame> {
+	fn usable_frames(memory_map: &'static MemoryMap) -> impl Iterator<Item=PhysFrame> {
 		let addr_ranges =
-			self.memory_map.iter()
+			memory_map.iter()
 				.filter(|r| r.region_type == MemoryRegionType::Usable)
 				.map(|r| r.range.start_addr()..r.range.end_addr());
-		
+
 		// End addr is guaranteed to be a multiple of 4096 away from start addr
 		let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
 		// create `PhysFrame` types from the start addresses
 		frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
 	}
+
+	/// Phys-offset-mapped pointer to the free-list link word stored at the start of `frame`.
+	unsafe fn next_pointer(&self, frame: PhysFrame) -> *mut u64 {
+		let virt = self.physical_memory_offset + frame.start_address().as_u64();
+		virt.as_mut_ptr()
+	}
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
 	fn allocate_frame(&mut self) -> Option<PhysFrame> {
-		// TODO: OPTIMIZE
-		let frame = self.usable_frames().nth(self.next);
-		self.next += 1;
-		frame
+		let frame = self.free_list_head?;
+		let next = unsafe { *self.next_pointer(frame) };
+
+		self.free_list_head = if next == u64::MAX {
+			None
+		} else {
+			Some(PhysFrame::containing_address(PhysAddr::new(next)))
+		};
+
+		Some(frame)
+	}
+}
+
+unsafe impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+	/// # Safety
+	/// `frame` must not still be in use, and must be part of the usable physical memory
+	/// mapped at `physical_memory_offset`.
+	unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+		let next = match self.free_list_head {
+			Some(head) => head.start_address().as_u64(),
+			None => u64::MAX,
+		};
+
+		*self.next_pointer(frame) = next;
+		self.free_list_head = Some(frame);
 	}
 }
 
@@ -71,6 +113,23 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
 	&mut *page_table_ptr
 }
 
+/// Maps `page` to a freshly allocated frame with `USER_ACCESSIBLE` set, so ring-3 code can
+/// touch it without taking a privilege-check page fault.
+pub fn map_user_page(
+	page: Page,
+	mapper: &mut OffsetPageTable,
+	frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+	let frame = frame_allocator.allocate_frame().ok_or(MapToError::FrameAllocationFailed)?;
+	let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+
+	unsafe {
+		mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+	}
+
+	Ok(())
+}
+
 /// Translates the given virtual address to the mapped physical address, or
 /// `None` if the address is not mapped.
 pub unsafe fn _translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr> {
@@ -12,7 +12,7 @@ use alloc::collections::{BTreeSet, BTreeMap};
 use spin::{Mutex, RwLock};
 use crate::processes::Name;
 use lazy_static::lazy_static;
-use core::sync::atomic::{AtomicI32, Ordering};
+use core::sync::atomic::{AtomicI32, AtomicI64, Ordering};
 
 pub type SemaphoreId = i64;
 
@@ -21,6 +21,16 @@ lazy_static! { // Using a spinlock semaphore to control my semaphore lol
 		RwLock::new(BTreeMap::new());
 }
 
+static NEXT_SEMAPHORE_ID: AtomicI64 = AtomicI64::new(0);
+
+/// Allocates a fresh id and inserts a new semaphore with `initial_count`, for
+/// `SyscallCommand::SemaphoreCreate`.
+pub fn create_semaphore(initial_count: i32) -> SemaphoreId {
+	let id = NEXT_SEMAPHORE_ID.fetch_add(1, Ordering::Relaxed);
+	SEMAPHORE_STORE.write().insert(id, Semaphore::new(initial_count));
+	id
+}
+
 #[derive(Debug)]
 pub struct Semaphore {
 	count: AtomicI32,
@@ -52,7 +62,13 @@ impl Semaphore {
 	pub fn check_and_pop_if_exists(&self, name: Name) -> bool {
 		self.queue.lock().remove(&name)
 	}
-	
+
+	/// Pops an arbitrary waiter off the queue, for `SyscallCommand::SemaphoreSignal` to
+	/// wake back up.
+	pub fn pop_waiter(&self) -> Option<Name> {
+		self.queue.lock().pop_first()
+	}
+
 	pub fn signal(&self) -> bool {
 		let old = self.count.fetch_add(1, Ordering::Relaxed);
 		old + 1 >= 0